@@ -1,10 +1,11 @@
 use anyhow::Result;
 use fuse::{
-    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen,
-    ReplyWrite, Request,
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyOpen, ReplyWrite, Request,
 };
-use libc::{EIO, ENOENT};
+use libc::{EIO, ENOENT, EROFS};
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 use fuse::consts::FOPEN_DIRECT_IO;
 use serenity::{
@@ -15,36 +16,233 @@ use serenity::{
     },
 };
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap, HashSet},
     ffi::OsStr,
-    time::{Duration, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 use tokio::runtime::Runtime;
 
 const TTL: Duration = Duration::from_secs(1);
 
+// Default lifetime of a cached, rendered channel transcript before it is refetched.
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(10);
+
+// Default cap on how many messages of history to pull per channel, to keep a
+// single refresh of a very old/busy channel from pulling it all into memory.
+const DEFAULT_MAX_MESSAGES: usize = 2000;
+
+// The largest page Discord's message-list endpoint will hand back at once.
+const PAGE_SIZE: usize = 100;
+
 // Map inodes to DiscordFiles
 type FileTree = BTreeMap<u64, DiscordFile>;
 
-struct DiscordFS<'a> {
-    discord: &'a Http,
-    files: FileTree,
+// A rendered channel transcript, kept around so repeated reads (FOPEN_DIRECT_IO
+// means every read() is a fresh syscall) don't each re-fetch from Discord.
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    fetched_at: Instant,
+    rendered: String,
+}
+
+// Swapped wholesale by `spawn_background_refresh` whenever guild/channel
+// topology changes, so mounted filesystems pick up new/renamed channels
+// without needing a remount.
+type SharedFileTree = Arc<Mutex<Arc<FileTree>>>;
+
+struct DiscordFS {
+    discord: Arc<Http>,
+    files: SharedFileTree,
+    cache: Arc<Mutex<BTreeMap<u64, CacheEntry>>>,
+    cache_ttl: Duration,
+    // Attachment bytes, keyed by inode. Unlike `cache`, these never go stale
+    // (a Discord attachment's content doesn't change under a fixed URL), so
+    // they're kept for the life of the mount once fetched once.
+    attachment_cache: Arc<Mutex<BTreeMap<u64, Arc<Vec<u8>>>>>,
+    runtime: Runtime,
+    http: reqwest::Client,
+    max_messages: usize,
+    // Inodes with at least one open handle, keyed to an open-handle count;
+    // the background poller only watches these so it doesn't hammer every
+    // channel in every mounted guild. Counted rather than a bare set so one
+    // handle's `release()` doesn't stop the poller out from under another
+    // handle on the same channel still open elsewhere.
+    active: Arc<Mutex<HashMap<u64, usize>>>,
+    read_only: bool,
+}
+
+fn cache_ttl_from_env() -> Duration {
+    std::env::var("DISCORD_FUSE_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_CACHE_TTL)
+}
+
+fn max_messages_from_env() -> usize {
+    std::env::var("DISCORD_FUSE_MAX_MESSAGES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_MESSAGES)
 }
 
-#[derive(Debug, Copy, Clone)]
+// Walks a channel's history backwards via `before=<message_id>` until Discord
+// stops returning full pages (the channel start) or `max_messages` is hit,
+// returning the result oldest-first.
+async fn fetch_channel_history(
+    discord: &Http,
+    channel: u64,
+    max_messages: usize,
+) -> Result<Vec<serenity::model::channel::Message>> {
+    let mut all = Vec::new();
+    let mut before = None;
+
+    loop {
+        let query = match before {
+            Some(id) => format!("?before={}&limit={}", id, PAGE_SIZE),
+            None => format!("?limit={}", PAGE_SIZE),
+        };
+        let page = discord.get_messages(channel, &query).await?;
+        if page.is_empty() {
+            break;
+        }
+
+        before = page.last().map(|message| message.id.0);
+        let page_len = page.len();
+        all.extend(page);
+
+        if page_len < PAGE_SIZE || all.len() >= max_messages {
+            break;
+        }
+    }
+
+    all.truncate(max_messages);
+    all.reverse();
+    Ok(all)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum DiscordFileType {
     Guild,
     ChannelFile(u64, u64),
+    // Directory of attachments belonging to a channel, named "<channel>.attachments".
+    AttachmentDir(u64),
+    // A single downloadable attachment. `size` mirrors `attr.size` so `getattr`
+    // doesn't need a network round-trip to answer.
+    Attachment { url: String, size: u64 },
+    // A channel category; text/voice/etc. channels nest underneath it.
+    Category,
+    // A non-text channel (voice, announcement, forum, ...) we don't render
+    // messages for. `kind` is Discord's channel type, for the placeholder body.
+    PlaceholderChannel { kind: String },
 }
 
-#[derive(Debug, Clone)]
+// Attachment directories and the attachment files inside them are synthesized
+// rather than fetched from Discord, so they need inodes that can't collide
+// with the real guild/channel snowflakes used elsewhere in the tree.
+fn attachments_dir_ino(channel: u64) -> u64 {
+    channel | (1 << 63)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct DiscordFile {
     filename: String,
     ty: DiscordFileType,
     parent: u64,
+    #[serde(with = "FileAttrDef")]
     attr: FileAttr,
 }
 
+// `FileAttr`/`FileType` live in the `fuse` crate, so they need serde's remote
+// derive to become (de)serializable for the on-disk index.
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "FileAttr")]
+struct FileAttrDef {
+    ino: u64,
+    size: u64,
+    blocks: u64,
+    atime: std::time::SystemTime,
+    mtime: std::time::SystemTime,
+    ctime: std::time::SystemTime,
+    crtime: std::time::SystemTime,
+    #[serde(with = "FileTypeDef")]
+    kind: FileType,
+    perm: u16,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u32,
+    flags: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "FileType")]
+enum FileTypeDef {
+    NamedPipe,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    RegularFile,
+    Symlink,
+    Socket,
+}
+
+// Bumped whenever `DiscordFile`/`DiscordFileType` change shape, so a stale
+// on-disk index is never loaded as if it matched the current schema.
+const INDEX_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct PersistedIndex {
+    version: u32,
+    files: FileTree,
+}
+
+fn index_path() -> std::path::PathBuf {
+    std::env::var_os("DISCORD_FUSE_INDEX_PATH")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::path::PathBuf::from("discord-fuse.index"))
+}
+
+// Loads the cached file tree if an index exists on disk and its schema
+// version matches; any mismatch or I/O error is treated as "no cache".
+fn load_cached_tree() -> Option<FileTree> {
+    let compressed = std::fs::read(index_path()).ok()?;
+    let raw = zstd::decode_all(&compressed[..]).ok()?;
+    let index: PersistedIndex = bincode::deserialize(&raw).ok()?;
+
+    if index.version != INDEX_SCHEMA_VERSION {
+        debug!("ignoring cached index: schema version mismatch");
+        return None;
+    }
+
+    Some(index.files)
+}
+
+fn save_tree_index(files: &FileTree) {
+    let index = PersistedIndex {
+        version: INDEX_SCHEMA_VERSION,
+        files: files.clone(),
+    };
+
+    let raw = match bincode::serialize(&index) {
+        Ok(raw) => raw,
+        Err(err) => {
+            debug!("failed to serialize file tree index: {}", err);
+            return;
+        }
+    };
+
+    match zstd::encode_all(&raw[..], 0) {
+        Ok(compressed) => {
+            if let Err(err) = std::fs::write(index_path(), compressed) {
+                debug!("failed to write file tree index: {}", err);
+            }
+        }
+        Err(err) => debug!("failed to compress file tree index: {}", err),
+    }
+}
+
 const ROOT_DIR_ATTR: FileAttr = FileAttr {
     ino: 1,
     size: 0,
@@ -62,13 +260,22 @@ const ROOT_DIR_ATTR: FileAttr = FileAttr {
     flags: 0,
 };
 
-impl<'a> Filesystem for DiscordFS<'a> {
+impl DiscordFS {
+    // Snapshots the current file tree. Cheap: it's just an `Arc` clone, and
+    // holding the returned snapshot means a concurrent topology refresh can't
+    // change the tree out from under a single lookup/getattr/readdir call.
+    fn files(&self) -> Arc<FileTree> {
+        self.files.lock().unwrap().clone()
+    }
+}
+
+impl Filesystem for DiscordFS {
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         debug!("lookup(parent: {}, name: {:?})", parent, name);
 
         let name = name.to_str().expect("Bad name!");
-        let result = self
-            .files
+        let files = self.files();
+        let result = files
             .iter()
             .filter(|&(_, v)| v.parent == parent)
             .find(|&(_, file)| file.filename.eq(name));
@@ -88,12 +295,20 @@ impl<'a> Filesystem for DiscordFS<'a> {
         debug!("getattr(ino: {})", ino);
 
         if ino == 1 {
-            reply.attr(&TTL, &ROOT_DIR_ATTR);
+            let mut attr = ROOT_DIR_ATTR;
+            if self.read_only {
+                attr.perm = 0o555;
+            }
+            reply.attr(&TTL, &attr);
             return;
         }
 
-        if let Some(file) = self.files.get(&ino) {
-            reply.attr(&TTL, &file.attr);
+        if let Some(file) = self.files().get(&ino) {
+            let mut attr = file.attr;
+            if let Some(entry) = self.cache.lock().unwrap().get(&ino) {
+                attr.size = entry.rendered.len() as u64;
+            }
+            reply.attr(&TTL, &attr);
         } else {
             debug!("ERROR: getattr(ino: {}): ENOENT", ino);
             reply.error(ENOENT);
@@ -102,10 +317,43 @@ impl<'a> Filesystem for DiscordFS<'a> {
 
     fn open(&mut self, _req: &Request<'_>, ino: u64, flags: u32, reply: ReplyOpen) {
         debug!("open(ino: {}, flags: {:#X})", ino, flags);
+
+        if let Some(DiscordFileType::ChannelFile(_, _)) =
+            self.files().get(&ino).map(|file| file.ty.clone())
+        {
+            *self.active.lock().unwrap().entry(ino).or_insert(0) += 1;
+        }
+
         // This is necessary so writes aren't split
         reply.opened(0, FOPEN_DIRECT_IO);
     }
 
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        debug!("release(ino: {})", ino);
+
+        // Stop watching this channel once every handle on it is closed, so
+        // the poller doesn't keep refetching a channel nobody is reading.
+        // Counted rather than a bare remove so a concurrent second handle on
+        // the same inode keeps it watched until it too is released.
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&ino) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&ino);
+            }
+        }
+        reply.ok();
+    }
+
     fn read(
         &mut self,
         _req: &Request,
@@ -116,36 +364,95 @@ impl<'a> Filesystem for DiscordFS<'a> {
         reply: ReplyData,
     ) {
         debug!("read(ino: {}, offset: {})", ino, offset);
+        let files = self.files();
 
-        if let Some(DiscordFileType::ChannelFile(_, id)) = self.files.get(&ino).map(|file| file.ty)
+        if let Some(DiscordFileType::PlaceholderChannel { kind }) =
+            files.get(&ino).map(|file| file.ty.clone())
         {
-            let mut v = Runtime::new()
+            let msg = format!("This is a {} channel; message history isn't available here.\n", kind);
+            if offset.is_positive() && (offset as usize) >= msg.len() {
+                reply.data(&[]);
+            } else {
+                reply.data(&msg.as_bytes()[offset as usize..]);
+            }
+            return;
+        }
+
+        if let Some(DiscordFileType::Attachment { url, .. }) =
+            files.get(&ino).map(|file| file.ty.clone())
+        {
+            let cached = self.attachment_cache.lock().unwrap().get(&ino).cloned();
+            let bytes = match cached {
+                Some(bytes) => Ok(bytes),
+                None => {
+                    let fetched = self.runtime.block_on(async {
+                        self.http.get(&url).send().await?.bytes().await
+                    });
+                    fetched.map(|bytes| {
+                        let bytes = Arc::new(bytes.to_vec());
+                        self.attachment_cache
+                            .lock()
+                            .unwrap()
+                            .insert(ino, bytes.clone());
+                        bytes
+                    })
+                }
+            };
+
+            match bytes {
+                Ok(bytes) => {
+                    let start = offset as usize;
+                    if start >= bytes.len() {
+                        reply.data(&[]);
+                    } else {
+                        let end = std::cmp::min(bytes.len(), start + _size as usize);
+                        reply.data(&bytes[start..end]);
+                    }
+                }
+                Err(_) => reply.error(EIO),
+            }
+            return;
+        }
+
+        if let Some(DiscordFileType::ChannelFile(_, id)) =
+            files.get(&ino).map(|file| file.ty.clone())
+        {
+            let fresh = self
+                .cache
+                .lock()
                 .unwrap()
-                .block_on(self.discord.get_messages(id, ""));
+                .get(&ino)
+                .map(|entry| entry.fetched_at.elapsed() < self.cache_ttl)
+                .unwrap_or(false);
 
-            if let Ok(channel) = v.as_mut() {
-                channel.reverse();
+            if !fresh {
+                let v = self.runtime.block_on(fetch_channel_history(
+                    &self.discord,
+                    id,
+                    self.max_messages,
+                ));
 
-                let msgs = channel
-                    .iter()
-                    .map(|message| {
-                        let attachments = message
-                            .attachments
-                            .iter()
-                            .map(|att| format!("{} ", att.url))
-                            .collect::<String>();
-                        format!(
-                            "{}#{:04}: {}\n",
-                            message.author.name,
-                            message.author.discriminator,
-                            if message.attachments.is_empty() {
-                                message.content.to_owned()
-                            } else {
-                                format!("{} {}", message.content, attachments)
-                            }
-                        )
-                    })
-                    .collect::<String>();
+                match v {
+                    Ok(channel) => {
+                        let rendered = render_messages(&channel);
+                        self.cache.lock().unwrap().insert(
+                            ino,
+                            CacheEntry {
+                                fetched_at: Instant::now(),
+                                rendered,
+                            },
+                        );
+                    }
+                    Err(_) if !self.cache.lock().unwrap().contains_key(&ino) => {
+                        reply.error(ENOENT);
+                        return;
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            if let Some(entry) = self.cache.lock().unwrap().get(&ino) {
+                let msgs = &entry.rendered;
                 if offset.is_positive() && (offset as usize) >= msgs.len() {
                     reply.data(&[]);
                 } else {
@@ -169,15 +476,37 @@ impl<'a> Filesystem for DiscordFS<'a> {
     ) {
         debug!("write(ino: {}, data: {:?})", ino, data);
 
-        if let Some(file) = self.files.get(&ino) {
-            if let DiscordFileType::ChannelFile(_, channel) = file.ty {
+        if self.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if let Some(file) = self.files().get(&ino) {
+            if let DiscordFileType::ChannelFile(_, channel) = file.ty.clone() {
                 let text = String::from_utf8_lossy(data);
-                let res = Runtime::new()
-                    .unwrap()
+                let res = self
+                    .runtime
                     .block_on(ChannelId(channel).say(&self.discord, &text));
 
                 match res {
-                    Ok(_) => reply.written(text.as_bytes().len() as u32),
+                    Ok(_) => {
+                        self.cache.lock().unwrap().remove(&ino);
+                        let refreshed = self.runtime.block_on(fetch_channel_history(
+                            &self.discord,
+                            channel,
+                            self.max_messages,
+                        ));
+                        if let Ok(msgs) = refreshed {
+                            self.cache.lock().unwrap().insert(
+                                ino,
+                                CacheEntry {
+                                    fetched_at: Instant::now(),
+                                    rendered: render_messages(&msgs),
+                                },
+                            );
+                        }
+                        reply.written(text.as_bytes().len() as u32);
+                    }
                     _ => reply.error(EIO),
                 }
             }
@@ -193,9 +522,9 @@ impl<'a> Filesystem for DiscordFS<'a> {
         mut reply: ReplyDirectory,
     ) {
         debug!("readdir(ino: {}, offset: {})", ino, offset);
-        if self.files.contains_key(&ino) || ino == 1 {
-            let files = self
-                .files
+        let tree = self.files();
+        if tree.contains_key(&ino) || ino == 1 {
+            let files = tree
                 .iter()
                 .filter(|&(_, v)| v.parent == ino)
                 .collect::<Vec<_>>();
@@ -211,8 +540,12 @@ impl<'a> Filesystem for DiscordFS<'a> {
                     (
                         *ino,
                         match file.ty {
-                            DiscordFileType::Guild => FileType::Directory,
-                            DiscordFileType::ChannelFile(_, _) => FileType::RegularFile,
+                            DiscordFileType::Guild
+                            | DiscordFileType::AttachmentDir(_)
+                            | DiscordFileType::Category => FileType::Directory,
+                            DiscordFileType::ChannelFile(_, _)
+                            | DiscordFileType::Attachment { .. }
+                            | DiscordFileType::PlaceholderChannel { .. } => FileType::RegularFile,
                         },
                         file.filename.as_str(),
                     )
@@ -230,6 +563,29 @@ impl<'a> Filesystem for DiscordFS<'a> {
     }
 }
 
+fn render_messages(channel: &[serenity::model::channel::Message]) -> String {
+    channel
+        .iter()
+        .map(|message| {
+            let attachments = message
+                .attachments
+                .iter()
+                .map(|att| format!("{} ", att.url))
+                .collect::<String>();
+            format!(
+                "{}#{:04}: {}\n",
+                message.author.name,
+                message.author.discriminator,
+                if message.attachments.is_empty() {
+                    message.content.to_owned()
+                } else {
+                    format!("{} {}", message.content, attachments)
+                }
+            )
+        })
+        .collect::<String>()
+}
+
 fn unique_name(base: &str, known_names: &[String]) -> String {
     let mut name = base.to_string();
 
@@ -246,7 +602,20 @@ fn unique_name(base: &str, known_names: &[String]) -> String {
     name
 }
 
-async fn build_file_tree(client: &Http) -> Result<FileTree> {
+// A cached index may have been written under a different `--read-only`
+// setting than this run, so channel file perms need to be repatched rather
+// than trusted as-is.
+fn apply_read_only(files: &mut FileTree, read_only: bool) {
+    let channel_file_perm = if read_only { 0o444 } else { 0o644 };
+    for file in files.values_mut() {
+        if let DiscordFileType::ChannelFile(_, _) = file.ty {
+            file.attr.perm = channel_file_perm;
+        }
+    }
+}
+
+async fn build_file_tree(client: &Http, read_only: bool, max_messages: usize) -> Result<FileTree> {
+    let channel_file_perm = if read_only { 0o444 } else { 0o644 };
     let guilds = client
         .get_guilds(&GuildPagination::After(GuildId(0)), 100)
         .await?;
@@ -285,32 +654,205 @@ async fn build_file_tree(client: &Http) -> Result<FileTree> {
 
         let channels = guild.id.channels(&client).await?;
 
-        let mut channel_names = vec![];
+        // Categories are created first so the channel pass below can nest
+        // text/voice/etc. channels directly underneath their folder.
+        let mut category_names = vec![];
+        let mut category_inos = HashSet::new();
 
         for (key, value) in &channels {
-            if value.kind != ChannelType::Text {
+            if value.kind != ChannelType::Category {
                 continue;
             }
 
-            let name = unique_name(&value.name, &channel_names);
-            channel_names.push(name.clone());
+            let name = unique_name(&value.name, &category_names);
+            category_names.push(name.clone());
+            category_inos.insert(key.0);
 
             files.insert(
                 key.0,
                 DiscordFile {
                     filename: name,
-                    ty: DiscordFileType::ChannelFile(guild.id.0, key.0),
+                    ty: DiscordFileType::Category,
                     parent: guild.id.0,
                     attr: FileAttr {
                         ino: key.0,
-                        size: u32::MAX as u64,
+                        size: 0,
+                        blocks: 0,
+                        atime: UNIX_EPOCH,
+                        mtime: UNIX_EPOCH,
+                        ctime: UNIX_EPOCH,
+                        crtime: UNIX_EPOCH,
+                        kind: FileType::Directory,
+                        perm: 0o555,
+                        nlink: 2,
+                        uid: 501,
+                        gid: 20,
+                        rdev: 0,
+                        flags: 0,
+                    },
+                },
+            );
+        }
+
+        // Name collisions are only resolved against siblings in the same
+        // directory, so track known names per parent rather than per guild.
+        let mut names_by_parent: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+        // Categories sit directly under the guild, next to uncategorized channels.
+        names_by_parent.insert(guild.id.0, category_names);
+
+        for (key, value) in &channels {
+            if value.kind == ChannelType::Category {
+                continue;
+            }
+
+            let parent = value
+                .category_id
+                .map(|category| category.0)
+                .filter(|category| category_inos.contains(category))
+                .unwrap_or(guild.id.0);
+
+            let known = names_by_parent.entry(parent).or_default();
+            let name = unique_name(&value.name, known);
+            known.push(name.clone());
+
+            if value.kind == ChannelType::Text {
+                files.insert(
+                    key.0,
+                    DiscordFile {
+                        filename: name,
+                        ty: DiscordFileType::ChannelFile(guild.id.0, key.0),
+                        parent,
+                        attr: FileAttr {
+                            ino: key.0,
+                            size: u32::MAX as u64,
+                            blocks: 0,
+                            atime: UNIX_EPOCH,
+                            mtime: UNIX_EPOCH,
+                            ctime: UNIX_EPOCH,
+                            crtime: UNIX_EPOCH,
+                            kind: FileType::RegularFile,
+                            perm: channel_file_perm,
+                            nlink: 1,
+                            uid: 501,
+                            gid: 20,
+                            rdev: 0,
+                            flags: 0,
+                        },
+                    },
+                );
+
+                insert_attachments(
+                    client,
+                    &mut files,
+                    parent,
+                    key.0,
+                    &value.name,
+                    max_messages,
+                )
+                .await?;
+            } else {
+                // Voice/announcement/forum/... channels: surface as a
+                // read-only placeholder rather than silently dropping them.
+                files.insert(
+                    key.0,
+                    DiscordFile {
+                        filename: name,
+                        ty: DiscordFileType::PlaceholderChannel {
+                            kind: format!("{:?}", value.kind),
+                        },
+                        parent,
+                        attr: FileAttr {
+                            ino: key.0,
+                            size: 0,
+                            blocks: 0,
+                            atime: UNIX_EPOCH,
+                            mtime: UNIX_EPOCH,
+                            ctime: UNIX_EPOCH,
+                            crtime: UNIX_EPOCH,
+                            kind: FileType::RegularFile,
+                            perm: 0o444,
+                            nlink: 1,
+                            uid: 501,
+                            gid: 20,
+                            rdev: 0,
+                            flags: 0,
+                        },
+                    },
+                );
+            }
+        }
+    }
+    Ok(files)
+}
+
+// Synthesizes a `<channel>.attachments/` directory holding one file per
+// message attachment across the channel's paginated history (at most
+// `max_messages` messages), so
+// attachments can be `cp`'d out of the mount like any other file.
+async fn insert_attachments(
+    client: &Http,
+    files: &mut FileTree,
+    parent_id: u64,
+    channel_id: u64,
+    channel_name: &str,
+    max_messages: usize,
+) -> Result<()> {
+    let messages = fetch_channel_history(client, channel_id, max_messages).await?;
+
+    let mut attachment_names = vec![];
+    let dir_ino = attachments_dir_ino(channel_id);
+
+    for message in &messages {
+        for att in &message.attachments {
+            if attachment_names.is_empty() {
+                files.insert(
+                    dir_ino,
+                    DiscordFile {
+                        filename: format!("{}.attachments", channel_name),
+                        ty: DiscordFileType::AttachmentDir(channel_id),
+                        parent: parent_id,
+                        attr: FileAttr {
+                            ino: dir_ino,
+                            size: 0,
+                            blocks: 0,
+                            atime: UNIX_EPOCH,
+                            mtime: UNIX_EPOCH,
+                            ctime: UNIX_EPOCH,
+                            crtime: UNIX_EPOCH,
+                            kind: FileType::Directory,
+                            perm: 0o555,
+                            nlink: 2,
+                            uid: 501,
+                            gid: 20,
+                            rdev: 0,
+                            flags: 0,
+                        },
+                    },
+                );
+            }
+
+            let name = format!("{}_{}", message.id.0, att.filename);
+            attachment_names.push(name.clone());
+
+            files.insert(
+                att.id.0,
+                DiscordFile {
+                    filename: name,
+                    ty: DiscordFileType::Attachment {
+                        url: att.url.clone(),
+                        size: att.size as u64,
+                    },
+                    parent: dir_ino,
+                    attr: FileAttr {
+                        ino: att.id.0,
+                        size: att.size as u64,
                         blocks: 0,
                         atime: UNIX_EPOCH,
                         mtime: UNIX_EPOCH,
                         ctime: UNIX_EPOCH,
                         crtime: UNIX_EPOCH,
                         kind: FileType::RegularFile,
-                        perm: 0o644,
+                        perm: 0o444,
                         nlink: 1,
                         uid: 501,
                         gid: 20,
@@ -321,29 +863,226 @@ async fn build_file_tree(client: &Http) -> Result<FileTree> {
             );
         }
     }
-    Ok(files)
+
+    Ok(())
+}
+
+// How often the background task re-walks guild/channel topology to keep the
+// on-disk index fresh for the next mount.
+const TOPOLOGY_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+fn spawn_background_refresh(
+    runtime: &Runtime,
+    client: Arc<Http>,
+    files: SharedFileTree,
+    read_only: bool,
+    max_messages: usize,
+) {
+    runtime.spawn(async move {
+        loop {
+            tokio::time::sleep(TOPOLOGY_REFRESH_INTERVAL).await;
+            match build_file_tree(&client, read_only, max_messages).await {
+                Ok(fresh) => {
+                    save_tree_index(&fresh);
+                    // Swap the live tree so mounted lookups/readdirs observe
+                    // the new topology without requiring a remount.
+                    *files.lock().unwrap() = Arc::new(fresh);
+                }
+                Err(err) => debug!("background topology refresh failed: {}", err),
+            }
+        }
+    });
+}
+
+// How often the poller checks actively-opened channels for new messages.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Polls actively-opened channels for new messages and folds them straight
+// into the shared cache, so the next `read()` serves fresh content without an
+// extra round-trip. `fuse`'s `Filesystem` trait doesn't expose a way to push
+// a kernel cache-invalidation notification for an inode from a background
+// task, so readers that rely on long-lived file handles (e.g. `tail -f`)
+// still pick this up on their next read once the 1s attr TTL lapses.
+fn spawn_message_poller(
+    runtime: &Runtime,
+    discord: Arc<Http>,
+    files: SharedFileTree,
+    active: Arc<Mutex<HashMap<u64, usize>>>,
+    cache: Arc<Mutex<BTreeMap<u64, CacheEntry>>>,
+    cache_ttl: Duration,
+    max_messages: usize,
+) {
+    runtime.spawn(async move {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let watched: Vec<(u64, u64)> = {
+                let files = files.lock().unwrap().clone();
+                let active = active.lock().unwrap();
+                let cache = cache.lock().unwrap();
+                files
+                    .iter()
+                    .filter(|(ino, _)| active.contains_key(ino))
+                    // Still-fresh entries get left alone; `read()` already
+                    // refetches them once the TTL lapses, so the poller only
+                    // needs to cover the gap for handles that aren't reading.
+                    .filter(|(ino, _)| {
+                        cache
+                            .get(ino)
+                            .map(|entry| entry.fetched_at.elapsed() >= cache_ttl)
+                            .unwrap_or(true)
+                    })
+                    .filter_map(|(ino, file)| match &file.ty {
+                        DiscordFileType::ChannelFile(_, channel) => Some((*ino, *channel)),
+                        _ => None,
+                    })
+                    .collect()
+            };
+
+            for (ino, channel) in watched {
+                match fetch_channel_history(&discord, channel, max_messages).await {
+                    Ok(messages) => {
+                        let rendered = render_messages(&messages);
+                        cache.lock().unwrap().insert(
+                            ino,
+                            CacheEntry {
+                                fetched_at: Instant::now(),
+                                rendered,
+                            },
+                        );
+                    }
+                    Err(err) => debug!("poll of channel {} failed: {}", channel, err),
+                }
+            }
+        }
+    });
+}
+
+// Parsed CLI/mount configuration, in place of the hand-rolled `-o` array
+// `main` used to pass straight into `fuse::mount`.
+struct MountOptions {
+    mountpoint: std::path::PathBuf,
+    read_only: bool,
+    auto_unmount: bool,
+    fsname: String,
+    subtype: Option<String>,
+}
+
+impl MountOptions {
+    fn from_args() -> Self {
+        let mut mountpoint = None;
+        let mut read_only = false;
+        let mut auto_unmount = false;
+        let mut fsname = "discordfuse".to_string();
+        let mut subtype = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--read-only" | "-r" => read_only = true,
+                "--auto-unmount" => auto_unmount = true,
+                "--fsname" => fsname = args.next().expect("--fsname requires a value"),
+                "--subtype" => subtype = Some(args.next().expect("--subtype requires a value")),
+                _ if mountpoint.is_none() => mountpoint = Some(std::path::PathBuf::from(arg)),
+                other => panic!("unexpected argument: {}", other),
+            }
+        }
+
+        MountOptions {
+            mountpoint: mountpoint.expect("usage: discord-fuse [flags] <mountpoint>"),
+            read_only,
+            auto_unmount,
+            fsname,
+            subtype,
+        }
+    }
+
+    fn fuse_args(&self) -> Vec<String> {
+        let mut opts = vec![format!("fsname={}", self.fsname)];
+        if let Some(subtype) = &self.subtype {
+            opts.push(format!("subtype={}", subtype));
+        }
+        if self.read_only {
+            opts.push("ro".to_string());
+        }
+        if self.auto_unmount {
+            opts.push("auto_unmount".to_string());
+        }
+
+        vec!["-o".to_string(), opts.join(",")]
+    }
 }
 
 fn main() -> Result<()> {
     env_logger::init();
     let token = std::env::var("DISCORD_TOKEN").expect("token");
-    let client = Http::new_with_token(&token);
+    let client = Arc::new(Http::new_with_token(&token));
+
+    let options = MountOptions::from_args();
+
+    let runtime = Runtime::new().unwrap();
+    let max_messages = max_messages_from_env();
+
+    let files = match load_cached_tree() {
+        Some(mut cached) => {
+            debug!("mounting from cached file tree index");
+            // The index may predate this run's `--read-only` flag.
+            apply_read_only(&mut cached, options.read_only);
+            cached
+        }
+        None => {
+            let fresh =
+                runtime.block_on(build_file_tree(&client, options.read_only, max_messages))?;
+            save_tree_index(&fresh);
+            fresh
+        }
+    };
+    let files: SharedFileTree = Arc::new(Mutex::new(Arc::new(files)));
+
+    spawn_background_refresh(
+        &runtime,
+        client.clone(),
+        files.clone(),
+        options.read_only,
+        max_messages,
+    );
+
+    let cache = Arc::new(Mutex::new(BTreeMap::new()));
+    let active = Arc::new(Mutex::new(HashMap::new()));
+    let cache_ttl = cache_ttl_from_env();
 
-    let files = Runtime::new().unwrap().block_on(build_file_tree(&client))?;
+    spawn_message_poller(
+        &runtime,
+        client.clone(),
+        files.clone(),
+        active.clone(),
+        cache.clone(),
+        cache_ttl,
+        max_messages,
+    );
 
-    let mountpoint = std::env::args_os().nth(1).unwrap();
-    let options = ["-o", "fsname=discordfuse"]
+    let mountpoint = options.mountpoint.clone();
+    let fuse_args = options.fuse_args();
+    let fuse_args = fuse_args
         .iter()
         .map(|o| o.as_ref())
         .collect::<Vec<&OsStr>>();
 
     fuse::mount(
         DiscordFS {
-            discord: &client,
+            discord: client.clone(),
             files,
+            cache,
+            cache_ttl,
+            attachment_cache: Arc::new(Mutex::new(BTreeMap::new())),
+            runtime,
+            http: reqwest::Client::new(),
+            max_messages,
+            active,
+            read_only: options.read_only,
         },
         &mountpoint,
-        &options,
+        &fuse_args,
     )
     .unwrap();
 